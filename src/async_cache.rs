@@ -1,19 +1,269 @@
-use crate::sync_cache::SyncCache;
+use crate::expiry::CanExpire;
+use crate::sync_cache::{CacheBuilder, CacheStats, RemovalCause, SyncCache};
 use chrono::Duration;
+use std::borrow::Borrow;
+use std::collections::HashMap;
 use std::future::Future;
 use std::hash::Hash;
 use std::marker::PhantomData;
 use std::pin::Pin;
-use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, RwLock};
+
+// Number of completed results a coalesced call's broadcast channel can buffer.
+// We only ever send one result per key, so this just needs to be non-zero.
+const IN_FLIGHT_CHANNEL_CAPACITY: usize = 1;
+
+// Removes a key's in-flight entry when the leader task that's computing it
+// finishes, panics, or is dropped/cancelled, so a poisoned key can never
+// wedge future callers behind a call that will never complete.
+struct InFlightGuard<Key, Val, ErrType>
+where
+    Key: Eq + Hash,
+{
+    in_flight: Arc<Mutex<HashMap<Key, broadcast::Sender<Result<Val, ErrType>>>>>,
+    key: Option<Key>,
+}
+
+impl<Key, Val, ErrType> Drop for InFlightGuard<Key, Val, ErrType>
+where
+    Key: Eq + Hash,
+{
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.in_flight.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+// Aborts the background housekeeper task spawned by `AsyncCacheBuilder::janitor` once the
+// last `AsyncCache` referencing it is dropped, so the task never outlives
+// the cache it's cleaning up.
+struct JanitorHandle {
+    abort: tokio::task::AbortHandle,
+}
+
+impl Drop for JanitorHandle {
+    fn drop(&mut self) {
+        self.abort.abort();
+    }
+}
+
+// Outcome of the check-or-register critical section in `coalesce`: either we
+// became the leader (and must call `f` ourselves), or another caller is
+// already computing this key and we just wait on its result. Returning this
+// as an owned value (rather than e.g. `drop()`-ing the `MutexGuard` partway
+// through the function) keeps the guard's lifetime confined to a sub-block
+// that ends before the next `.await`, so it never has to be `Send`.
+enum CoalesceRole<Val, ErrType> {
+    Leader(broadcast::Sender<Result<Val, ErrType>>),
+    Follower(broadcast::Receiver<Result<Val, ErrType>>),
+}
+
+// Shared single-flight bookkeeping behind `cache_fn` and
+// `cache_fn_self_expiring`: check the cache, then atomically check-or-register
+// an in-flight entry for `key`, call `f` only if we became the leader, and
+// fan the result out to any callers who coalesced onto us. If the leader
+// panics (or is cancelled) before sending a result, coalesced callers retry
+// rather than propagating an unrelated panic.
+//
+// `f` may return any `Item` (a bare `Val`, or a `(Val, Duration)` pair with a
+// TTL); `to_val` extracts the `Val` half to cache/return, and `store` does
+// the actual write into the cache (`set` vs `set_self_expiring`).
+async fn coalesce<Key, Val, ErrType, Item, F, Fut, StoreFut>(
+    cache: &Arc<RwLock<SyncCache<Key, Val>>>,
+    in_flight: &Arc<Mutex<HashMap<Key, broadcast::Sender<Result<Val, ErrType>>>>>,
+    key: Key,
+    f: &F,
+    to_val: impl Fn(&Item) -> Val,
+    store: impl Fn(Key, Item) -> StoreFut,
+) -> Result<Val, ErrType>
+where
+    Key: Eq + Hash + Clone,
+    Val: Clone,
+    ErrType: Clone,
+    F: Fn(Key) -> Fut,
+    Fut: Future<Output = Result<Item, ErrType>>,
+    StoreFut: Future<Output = ()>,
+{
+    loop {
+        // Try getting the value from the cache first
+        if let Some(val) = cache.read().await.get(&key) {
+            return Ok(val.clone());
+        }
+
+        // Check whether another caller is already computing this key and,
+        // if not, register ourselves as the leader, all within one critical
+        // section -- otherwise two concurrent callers could both see "no
+        // entry" and each become a leader. The `MutexGuard` lives only inside
+        // this block, so it's dropped before the `match` below ever awaits.
+        let role = {
+            let mut in_flight_guard = in_flight.lock().unwrap();
+            if let Some(sender) = in_flight_guard.get(&key) {
+                CoalesceRole::Follower(sender.subscribe())
+            } else {
+                let (sender, _) = broadcast::channel(IN_FLIGHT_CHANNEL_CAPACITY);
+                in_flight_guard.insert(key.clone(), sender.clone());
+                CoalesceRole::Leader(sender)
+            }
+        };
+
+        let sender = match role {
+            CoalesceRole::Follower(mut receiver) => match receiver.recv().await {
+                Ok(result) => return result,
+                // The leader panicked (or was cancelled) before sending a
+                // result, dropping its sender without fanning anything out.
+                // Its in-flight entry is already cleaned up by
+                // `InFlightGuard`, so retry rather than failing a call that
+                // never touched the panic: we'll either coalesce onto a new
+                // leader or become one ourselves.
+                Err(_) => continue,
+            },
+            CoalesceRole::Leader(sender) => sender,
+        };
+
+        // We're the leader: call the function, fanning the result out to
+        // anyone who coalesced onto us once we're done.
+        let _guard = InFlightGuard {
+            in_flight: in_flight.clone(),
+            key: Some(key.clone()),
+        };
+
+        let result = f(key.clone()).await;
+
+        // Store the result in the cache, as long as the function did not error
+        let result = match result {
+            Ok(item) => {
+                let val = to_val(&item);
+                store(key, item).await;
+                Ok(val)
+            }
+            Err(err) => Err(err),
+        };
+
+        // Fan the result out to any callers who coalesced onto us, whether it's
+        // a value or an error, then return our own copy
+        let _ = sender.send(result.clone());
+        return result;
+    }
+}
 
 #[derive(Clone)]
 pub struct AsyncCache<Key, Val> {
     cache: Arc<RwLock<SyncCache<Key, Val>>>,
+    // Set by `AsyncCacheBuilder::janitor`. When present, a background task is
+    // already removing expired entries, so `get` can skip the lazy write-lock
+    // upgrade and just filter out entries whose own expiry has passed.
+    janitor: Option<Arc<JanitorHandle>>,
     _key: PhantomData<Key>,
     _val: PhantomData<Val>,
 }
 
+// Builds an `AsyncCache` with any combination of capacity, weigher, eviction
+// listener, and background janitor -- these are all independent, and a cache
+// may reasonably want more than one at once (e.g. a weigher *and* an
+// eviction listener *and* a janitor).
+pub struct AsyncCacheBuilder<Key, Val> {
+    inner: CacheBuilder<Key, Val>,
+    janitor_interval: Option<Duration>,
+}
+
+impl<Key, Val> Default for AsyncCacheBuilder<Key, Val> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Key, Val> AsyncCacheBuilder<Key, Val> {
+    #[inline]
+    pub fn new() -> Self {
+        AsyncCacheBuilder {
+            inner: CacheBuilder::new(),
+            janitor_interval: None,
+        }
+    }
+
+    // Bounds the cache to at most `capacity` entries, evicting the
+    // soonest-expiring entry once it's full.
+    #[inline]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.inner = self.inner.capacity(capacity);
+        self
+    }
+
+    // Bounds the cache by total entry weight (as computed by `weigher`)
+    // rather than entry count, so the memoized `cache_fn` path can bound by
+    // e.g. total bytes cached instead of number of entries.
+    #[inline]
+    pub fn weigher(
+        mut self,
+        max_weight: usize,
+        weigher: impl Fn(&Key, &Val) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        self.inner = self.inner.weigher(max_weight, weigher);
+        self
+    }
+
+    // Invokes `listener` with the key, value, and reason whenever an entry
+    // leaves the cache (see `RemovalCause`).
+    #[inline]
+    pub fn eviction_listener(
+        mut self,
+        listener: impl Fn(Key, Val, RemovalCause) + Send + Sync + 'static,
+    ) -> Self {
+        self.inner = self.inner.eviction_listener(listener);
+        self
+    }
+
+    // Adds a background housekeeper task that wakes up every `interval` and
+    // removes expired entries (moka-style), instead of relying on `get` to
+    // clean them up lazily. The task is cancelled when the last `AsyncCache`
+    // referencing it is dropped.
+    //
+    // With a janitor configured, `get` never needs to upgrade to a write lock
+    // to clean up: it just filters out entries whose own expiry has already
+    // passed, which also avoids the brief window where lazy cleanup lets a
+    // reader observe a stale value under read/write contention.
+    #[inline]
+    pub fn janitor(mut self, interval: Duration) -> Self {
+        self.janitor_interval = Some(interval);
+        self
+    }
+
+    pub fn build(self) -> AsyncCache<Key, Val>
+    where
+        Key: Eq + Hash + Clone + Send + Sync + 'static,
+        Val: Clone + Send + Sync + 'static,
+    {
+        let cache = Arc::new(RwLock::new(self.inner.build()));
+        let janitor = self.janitor_interval.map(|interval| {
+            let interval = interval
+                .to_std()
+                .expect("janitor interval must not be negative");
+            let janitor_cache = cache.clone();
+            let join_handle = tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(interval);
+                // The first tick fires immediately; nothing is expired yet on a
+                // freshly created cache, so skip it.
+                ticker.tick().await;
+                loop {
+                    ticker.tick().await;
+                    janitor_cache.write().await.remove_expired_items();
+                }
+            });
+            Arc::new(JanitorHandle {
+                abort: join_handle.abort_handle(),
+            })
+        });
+        AsyncCache {
+            cache,
+            janitor,
+            _key: PhantomData,
+            _val: PhantomData,
+        }
+    }
+}
+
 impl<Key, Val> AsyncCache<Key, Val>
 where
     Key: Eq + Hash + Clone,
@@ -23,18 +273,19 @@ where
     pub fn new() -> Self {
         AsyncCache {
             cache: Arc::new(RwLock::new(SyncCache::new())),
+            janitor: None,
             _key: PhantomData,
             _val: PhantomData,
         }
     }
 
+    // Returns a builder for configuring a cache with any combination of
+    // capacity, weigher, eviction listener, and background janitor (see
+    // `AsyncCacheBuilder`), rather than picking a single one of them via a
+    // dedicated constructor.
     #[inline]
-    pub fn with_capacity(capacity: usize) -> Self {
-        AsyncCache {
-            cache: Arc::new(RwLock::new(SyncCache::with_capacity(capacity))),
-            _key: PhantomData,
-            _val: PhantomData,
-        }
+    pub fn builder() -> AsyncCacheBuilder<Key, Val> {
+        AsyncCacheBuilder::new()
     }
 
     // Returns the value corresponding to the given key if it is in the cache.
@@ -47,7 +298,17 @@ where
     // To avoid cloning objects that are expensive to clone, simply wrap
     // those objects in an Arc.
     #[inline]
-    pub async fn get(&self, key: &Key) -> Option<Val> {
+    pub async fn get<Q>(&self, key: &Q) -> Option<Val>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        // With a janitor running, expired entries are someone else's
+        // problem: just filter them out of the read, no write lock needed.
+        if self.janitor.is_some() {
+            return self.cache.read().await.get_if_live(key).cloned();
+        }
+
         // TODO as soon as a single key expires, every get command will try to get a write lock unnecessarily
         // only one actually needs to
         // also, this does not guarantee that stale data cannot be read if there is read/write contention
@@ -69,7 +330,11 @@ where
     }
 
     #[inline]
-    pub async fn remove(&self, key: &Key) -> bool {
+    pub async fn remove<Q>(&self, key: &Q) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
         self.cache.write().await.remove(key)
     }
 
@@ -88,36 +353,108 @@ where
         self.cache.read().await.is_empty()
     }
 
+    // Returns a snapshot of the cache's hit/miss/eviction/expiration counters
+    #[inline]
+    pub async fn stats(&self) -> CacheStats {
+        self.cache.read().await.stats()
+    }
+
     // Returns a version of the given function that caches the return values
-    // using the input as the Key and the returned Duration as the value's TTL
+    // using the input as the Key and the returned Duration as the value's TTL.
+    //
+    // Concurrent calls for the same uncached key are coalesced ("single-flight"):
+    // only the first caller actually invokes `f`, and the other callers await its
+    // result instead of each triggering their own call.
     pub fn cache_fn<'a, Fut, ErrType>(
         &self,
-        f: impl Fn(Key) -> Fut + 'a,
-    ) -> impl Fn(Key) -> Pin<Box<dyn Future<Output = Result<Val, ErrType>> + 'a>> + 'a
+        f: impl Fn(Key) -> Fut + Send + Sync + 'a,
+    ) -> impl Fn(Key) -> Pin<Box<dyn Future<Output = Result<Val, ErrType>> + Send + 'a>> + 'a
     where
-        Key: 'a,
-        Val: 'a,
+        Key: Send + Sync + 'a,
+        Val: Send + Sync + 'a,
+        ErrType: Clone + Send + Sync + 'a,
         // TODO maybe use std::time::Duration or u32 in the function signature
         // TODO maybe define a trait like GetTtl on the return type instead of requiring it to be a tuple
-        Fut: Future<Output = Result<(Val, Duration), ErrType>> + 'static,
+        Fut: Future<Output = Result<(Val, Duration), ErrType>> + Send + 'static,
     {
         let f = Arc::new(f);
         let cache = self.cache.clone();
+        let in_flight: Arc<Mutex<HashMap<Key, broadcast::Sender<Result<Val, ErrType>>>>> =
+            Arc::new(Mutex::new(HashMap::new()));
         move |key| {
             let cache = cache.clone();
             let f = f.clone();
+            let in_flight = in_flight.clone();
 
             Box::pin(async move {
-                // Try getting the value from the cache fist
-                if let Some(val) = cache.read().await.get(&key) {
-                    return Ok(val.clone());
-                }
+                coalesce(
+                    &cache,
+                    &in_flight,
+                    key,
+                    &*f,
+                    |(val, _ttl): &(Val, Duration)| val.clone(),
+                    |key, (val, ttl)| {
+                        let cache = cache.clone();
+                        async move {
+                            cache.write().await.set(key, val, ttl);
+                        }
+                    },
+                )
+                .await
+            })
+        }
+    }
+}
 
-                // If the result wasn't already in the cache, call the function
-                // and store the result in the cache (as long as the function did not error)
-                let (val, ttl) = f(key.clone()).await?;
-                cache.write().await.set(key, val.clone(), ttl);
-                Ok(val)
+impl<Key, Val> AsyncCache<Key, Val>
+where
+    Key: Eq + Hash + Clone,
+    Val: Clone + CanExpire,
+{
+    // Like `set`, but for a value that knows its own expiry (see `CanExpire`)
+    // instead of taking a separate TTL.
+    #[inline]
+    pub async fn set_self_expiring(&self, key: Key, value: Val) -> bool {
+        self.cache.write().await.set_self_expiring(key, value)
+    }
+
+    // Like `cache_fn`, but for a wrapped function that returns a `Val: CanExpire`
+    // directly (no separate TTL), e.g. an HTTP response carrying its own
+    // `Cache-Control` header. Coalesces concurrent misses the same way `cache_fn` does.
+    pub fn cache_fn_self_expiring<'a, Fut, ErrType>(
+        &self,
+        f: impl Fn(Key) -> Fut + Send + Sync + 'a,
+    ) -> impl Fn(Key) -> Pin<Box<dyn Future<Output = Result<Val, ErrType>> + Send + 'a>> + 'a
+    where
+        Key: Send + Sync + 'a,
+        Val: Send + Sync + 'a,
+        ErrType: Clone + Send + Sync + 'a,
+        Fut: Future<Output = Result<Val, ErrType>> + Send + 'static,
+    {
+        let f = Arc::new(f);
+        let cache = self.cache.clone();
+        let in_flight: Arc<Mutex<HashMap<Key, broadcast::Sender<Result<Val, ErrType>>>>> =
+            Arc::new(Mutex::new(HashMap::new()));
+        move |key| {
+            let cache = cache.clone();
+            let f = f.clone();
+            let in_flight = in_flight.clone();
+
+            Box::pin(async move {
+                coalesce(
+                    &cache,
+                    &in_flight,
+                    key,
+                    &*f,
+                    |val: &Val| val.clone(),
+                    |key, val| {
+                        let cache = cache.clone();
+                        async move {
+                            cache.write().await.set_self_expiring(key, val);
+                        }
+                    },
+                )
+                .await
             })
         }
     }
@@ -132,7 +469,7 @@ mod tests {
 
     #[tokio::test]
     async fn basic_get_set() {
-        let cache = AsyncCache::with_capacity(5);
+        let cache = AsyncCache::builder().capacity(5).build();
         cache.set("a", 1u32, Duration::milliseconds(200)).await;
         assert_eq!(cache.get(&"a").await, Some(1));
 
@@ -144,7 +481,7 @@ mod tests {
 
     #[tokio::test]
     async fn getting_expired_value() {
-        let cache = AsyncCache::with_capacity(5);
+        let cache = AsyncCache::builder().capacity(5).build();
         // expired
         cache.set("a", 1u32, Duration::milliseconds(-200)).await;
         // not expired
@@ -154,6 +491,23 @@ mod tests {
         assert_eq!(cache.get(&"b").await, Some(2));
     }
 
+    #[tokio::test]
+    async fn janitor_removes_expired_items_in_background() {
+        let cache = AsyncCache::builder()
+            .janitor(Duration::milliseconds(20))
+            .build();
+        cache.set("a", 1u32, Duration::milliseconds(-200)).await; // already expired
+        cache.set("b", 2u32, Duration::hours(1)).await;
+
+        // the read path filters out "a" itself, without waiting for the janitor
+        assert_eq!(cache.get(&"a").await, None);
+        assert_eq!(cache.get(&"b").await, Some(2));
+
+        // give the background task a chance to actually remove "a" from the map
+        tokio::time::sleep(std::time::Duration::from_millis(60)).await;
+        assert_eq!(cache.len().await, 1);
+    }
+
     #[tokio::test]
     async fn cache_fn() {
         let calls = Arc::new(AtomicUsize::new(0));
@@ -168,7 +522,7 @@ mod tests {
                 }
             }
         };
-        let cache = AsyncCache::with_capacity(3);
+        let cache = AsyncCache::builder().capacity(3).build();
         let cached = cache.cache_fn(f);
 
         // passes through error and does not cache it
@@ -183,4 +537,65 @@ mod tests {
         assert_eq!(cached(1).await, Ok("1".to_string()));
         assert_eq!(calls.load(Ordering::SeqCst), 2);
     }
+
+    #[tokio::test]
+    async fn cache_fn_coalesces_concurrent_misses() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let f = move |num: u32| {
+            let calls = calls_clone.clone();
+            async move {
+                calls.fetch_add(1, Ordering::Relaxed);
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                Ok::<_, &'static str>((format!("{}", num), Duration::seconds(10)))
+            }
+        };
+        let cache = AsyncCache::builder().capacity(3).build();
+        let cached = Arc::new(cache.cache_fn(f));
+
+        let handles: Vec<_> = (0..10)
+            .map(|_| {
+                let cached = cached.clone();
+                spawn(async move { cached(1).await })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), Ok("1".to_string()));
+        }
+
+        // only the first caller should have actually run the function
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    struct SelfExpiring(u32, bool);
+
+    impl crate::expiry::CanExpire for SelfExpiring {
+        fn is_expired(&self, _now: chrono::DateTime<chrono::Utc>) -> bool {
+            self.1
+        }
+    }
+
+    #[tokio::test]
+    async fn cache_fn_self_expiring() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let calls_clone = calls.clone();
+        let f = move |num: u32| {
+            let calls = calls_clone.clone();
+            async move {
+                calls.fetch_add(1, Ordering::Relaxed);
+                Ok::<_, &'static str>(SelfExpiring(num, false))
+            }
+        };
+        let cache = AsyncCache::builder().capacity(3).build();
+        let cached = cache.cache_fn_self_expiring(f);
+
+        assert_eq!(cached(1).await, Ok(SelfExpiring(1, false)));
+        assert_eq!(cache.get(&1).await, Some(SelfExpiring(1, false)));
+
+        // does not call the function again if it is cached
+        assert_eq!(cached(1).await, Ok(SelfExpiring(1, false)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
 }
@@ -0,0 +1,9 @@
+use chrono::{DateTime, Utc};
+
+// Implemented by values that know their own expiry, so they can be cached
+// via `set_self_expiring` without also having to track a separate TTL
+// (e.g. an HTTP response carrying its own `Cache-Control: max-age` or
+// `Expires` header).
+pub trait CanExpire {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool;
+}
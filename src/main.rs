@@ -1,4 +1,5 @@
 mod async_cache;
+mod expiry;
 mod sync_cache;
 
 use async_cache::AsyncCache;
@@ -1,16 +1,182 @@
+use crate::expiry::CanExpire;
 use chrono::{DateTime, Duration, DurationRound, Utc};
 use skiplist::SkipMap;
-use std::collections::HashMap;
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// Far enough in the future that count-based eviction only removes a
+// self-expiring entry after every TTL-based entry has had a chance to, while
+// staying well clear of `DateTime<Utc>`'s range so `duration_trunc` can't overflow.
+fn self_expiring_sentinel_ttl() -> Duration {
+    Duration::weeks(52 * 100)
+}
+
+// Why an entry left the cache, passed to an eviction listener registered via
+// `CacheBuilder::eviction_listener`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemovalCause {
+    // Removed because its TTL (or `CanExpire::is_expired`) elapsed
+    Expired,
+    // Removed to stay within `capacity`/`max_weight`
+    Capacity,
+    // Overwritten by a new value for the same key via `set`/`set_self_expiring`
+    Replaced,
+    // Removed via `remove` or `clear`
+    Explicit,
+}
+
+// Snapshot of a cache's hit/miss/eviction/expiration counters, returned by `stats()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+}
+
+// Atomic counters backing `CacheStats`. These use atomics (rather than plain
+// `u64`s bumped under `&mut self`) so that `get` can record hits and misses
+// without needing a write lock when wrapped in an `AsyncCache`.
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl Counters {
+    #[inline]
+    fn snapshot(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+        }
+    }
+}
 
 // Synchronous, non-thread-safe cache backed by a HashMap
 // and a SkipList of key expirations
 pub struct SyncCache<Key, Val> {
     // Map of the key to the cached value and the expiry
     map: HashMap<Key, (Val, DateTime<Utc>)>,
+    // Entry-count limit set via `CacheBuilder::capacity`, enforced on `set`. Tracked
+    // explicitly rather than compared against `map.capacity()`: hashbrown
+    // rounds small requested capacities up, so `map.len() == map.capacity()`
+    // doesn't reliably fire at the capacity the caller asked for.
+    capacity: Option<usize>,
     // Sorted map from expiry date to a list of keys expiring at that time
     // TODO bucket the expiries into groups for more efficient removal
     expiries: SkipMap<DateTime<Utc>, Vec<Key>>,
+    // Optional function used to compute each entry's weight, for weight-based
+    // (rather than just entry-count-based) capacity bounding
+    weigher: Option<Arc<dyn Fn(&Key, &Val) -> usize + Send + Sync>>,
+    // Limit on the sum of all entries' weights, enforced on `set` when a weigher is configured
+    max_weight: Option<usize>,
+    // Running sum of every entry's weight, kept in sync with `map` on every insert/remove
+    total_weight: usize,
+    // Hit/miss/eviction/expiration counters, exposed via `stats()`
+    counters: Counters,
+    // Keys inserted via `set_self_expiring`, whose expiry is determined by
+    // their own `CanExpire::is_expired` rather than the `expiries` skiplist
+    self_expiring_keys: HashSet<Key>,
+    // `Val::is_expired` as a function pointer, set the first time
+    // `set_self_expiring` is called. Stored this way (rather than requiring
+    // `Val: CanExpire` on the whole type) so `SyncCache<Key, Val>` works
+    // whether or not `Val` implements `CanExpire`.
+    is_expired_fn: Option<fn(&Val, DateTime<Utc>) -> bool>,
+    // Optional callback invoked with the removed key/value whenever an entry
+    // leaves the cache, along with the reason
+    listener: Option<Arc<dyn Fn(Key, Val, RemovalCause) + Send + Sync>>,
+}
+
+// Builds a `SyncCache` with any combination of capacity, weigher, and
+// eviction listener, since those are all independent and a cache may
+// reasonably want more than one at once (e.g. a weigher *and* an eviction
+// listener to log what gets weighed out).
+pub struct CacheBuilder<Key, Val> {
+    capacity: Option<usize>,
+    weigher: Option<Arc<dyn Fn(&Key, &Val) -> usize + Send + Sync>>,
+    max_weight: Option<usize>,
+    listener: Option<Arc<dyn Fn(Key, Val, RemovalCause) + Send + Sync>>,
+}
+
+impl<Key, Val> Default for CacheBuilder<Key, Val> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Key, Val> CacheBuilder<Key, Val> {
+    #[inline]
+    pub fn new() -> Self {
+        CacheBuilder {
+            capacity: None,
+            weigher: None,
+            max_weight: None,
+            listener: None,
+        }
+    }
+
+    // Bounds the cache to at most `capacity` entries, evicting the
+    // soonest-expiring entry once it's full.
+    #[inline]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    // Bounds the cache by total entry weight (as computed by `weigher`)
+    // rather than entry count. After every `set`, entries are evicted
+    // (soonest-expiring first) until `total_weight` is back under `max_weight`.
+    #[inline]
+    pub fn weigher(
+        mut self,
+        max_weight: usize,
+        weigher: impl Fn(&Key, &Val) -> usize + Send + Sync + 'static,
+    ) -> Self {
+        self.max_weight = Some(max_weight);
+        self.weigher = Some(Arc::new(weigher));
+        self
+    }
+
+    // Invokes `listener` with the key, value, and reason whenever an entry
+    // leaves the cache (see `RemovalCause`).
+    #[inline]
+    pub fn eviction_listener(
+        mut self,
+        listener: impl Fn(Key, Val, RemovalCause) + Send + Sync + 'static,
+    ) -> Self {
+        self.listener = Some(Arc::new(listener));
+        self
+    }
+
+    #[inline]
+    pub fn build(self) -> SyncCache<Key, Val>
+    where
+        Key: Eq + Hash + Clone,
+    {
+        SyncCache {
+            map: match self.capacity {
+                Some(capacity) => HashMap::with_capacity(capacity),
+                None => HashMap::new(),
+            },
+            capacity: self.capacity,
+            expiries: SkipMap::new(),
+            weigher: self.weigher,
+            max_weight: self.max_weight,
+            total_weight: 0,
+            counters: Counters::default(),
+            self_expiring_keys: HashSet::new(),
+            is_expired_fn: None,
+            listener: self.listener,
+        }
+    }
 }
 
 impl<Key, Val> SyncCache<Key, Val>
@@ -21,22 +187,59 @@ where
     pub fn new() -> Self {
         SyncCache {
             map: HashMap::new(),
+            capacity: None,
             expiries: SkipMap::new(),
+            weigher: None,
+            max_weight: None,
+            total_weight: 0,
+            counters: Counters::default(),
+            self_expiring_keys: HashSet::new(),
+            is_expired_fn: None,
+            listener: None,
         }
     }
 
+    // Returns a builder for configuring a cache with any combination of
+    // capacity, weigher, and eviction listener (see `CacheBuilder`), rather
+    // than picking a single one of them via a dedicated constructor.
     #[inline]
-    pub fn with_capacity(capacity: usize) -> Self {
-        SyncCache {
-            map: HashMap::with_capacity(capacity),
-            expiries: SkipMap::new(),
+    pub fn builder() -> CacheBuilder<Key, Val> {
+        CacheBuilder::new()
+    }
+
+    #[inline]
+    fn weight_of(&self, key: &Key, val: &Val) -> usize {
+        self.weigher.as_ref().map_or(0, |weigher| weigher(key, val))
+    }
+
+    #[inline]
+    fn notify_listener(&self, key: Key, val: Val, cause: RemovalCause) {
+        if let Some(listener) = &self.listener {
+            listener(key, val, cause);
         }
     }
 
     #[inline]
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
+    #[inline]
+    pub fn stats(&self) -> CacheStats {
+        self.counters.snapshot()
+    }
+
     pub fn clear(&mut self) {
-        self.map.clear();
+        if let Some(listener) = self.listener.clone() {
+            for (key, (val, _)) in self.map.drain() {
+                listener(key, val, RemovalCause::Explicit);
+            }
+        } else {
+            self.map.clear();
+        }
         self.expiries.clear();
+        self.self_expiring_keys.clear();
+        self.total_weight = 0;
     }
 
     #[inline]
@@ -44,14 +247,56 @@ where
         self.map.len()
     }
 
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
     #[inline]
     pub fn capacity(&self) -> usize {
         self.map.capacity()
     }
 
     #[inline]
-    pub fn get(&self, key: &Key) -> Option<&Val> {
-        self.map.get(key).map(|(val, _)| val)
+    pub fn get<Q>(&self, key: &Q) -> Option<&Val>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let val = self.map.get(key).map(|(val, _)| val);
+        if val.is_some() {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        val
+    }
+
+    // Like `get`, but also treats an entry as absent if its own expiry has
+    // already passed, without removing it or touching `expiries`/`map`.
+    // Used by `AsyncCache::get` when a background janitor task (see
+    // `AsyncCacheBuilder::janitor`) is responsible for actually removing
+    // expired entries, so the read path never needs to upgrade to a write lock.
+    #[inline]
+    pub fn get_if_live<Q>(&self, key: &Q) -> Option<&Val>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let entry = self.map.get(key).filter(|(val, expiry)| {
+            if self.self_expiring_keys.contains(key) {
+                self.is_expired_fn
+                    .is_none_or(|is_expired| !is_expired(val, Utc::now()))
+            } else {
+                *expiry > Utc::now()
+            }
+        });
+        if entry.is_some() {
+            self.counters.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        }
+        entry.map(|(val, _)| val)
     }
 
     // Insert the given key and value.
@@ -64,23 +309,38 @@ where
             .duration_trunc(Duration::milliseconds(10))
             .unwrap();
 
-        // Remove the previous expiry if there was one
-        let had_key = if let Some((_, expiry)) = self.map.get(&key) {
-            if let Some(key_list) = self.expiries.get_mut(&expiry) {
+        // Remove the previous entry's expiry and weight if there was one
+        let had_key = if let Some((old_val, old_expiry)) = self.map.remove(&key) {
+            self.total_weight -= self.weight_of(&key, &old_val);
+            let mut bucket_empty = false;
+            if let Some(key_list) = self.expiries.get_mut(&old_expiry) {
                 if let Some(index) = key_list.iter().position(|k| *k == key) {
                     key_list.remove(index);
                 }
+                bucket_empty = key_list.is_empty();
             };
+            if bucket_empty {
+                self.expiries.remove(&old_expiry);
+            }
+            self.notify_listener(key.clone(), old_val, RemovalCause::Replaced);
             true
         } else {
             false
         };
 
-        // If the map is at capacity, evict one entry before inserting
-        if !had_key && self.map.len() == self.map.capacity() {
-            self.evict();
+        // If a capacity limit is configured and we're at it, evict one entry
+        // before inserting. `self.capacity` (rather than `map.capacity()`,
+        // which hashbrown may round up past what was requested) is what
+        // actually bounds the entry count here.
+        if !had_key {
+            if let Some(capacity) = self.capacity {
+                if self.map.len() >= capacity {
+                    self.evict();
+                }
+            }
         }
 
+        self.total_weight += self.weight_of(&key, &value);
         self.map.insert(key.clone(), (value, expiry.clone()));
 
         // Insert into the expiry map (or add the key to the list of keys
@@ -91,13 +351,42 @@ where
             self.expiries.insert(expiry, vec![key]);
         }
 
+        // If a weight limit is configured, evict (soonest-expiring first) until
+        // we're back under it
+        if let Some(max_weight) = self.max_weight {
+            while self.total_weight > max_weight {
+                self.evict();
+            }
+        }
+
         had_key
     }
 
     #[inline]
-    pub fn remove(&mut self, key: &Key) -> bool {
-        if let Some((_, expiry)) = self.map.remove(&key) {
-            self.expiries.remove(&expiry);
+    pub fn remove<Q>(&mut self, key: &Q) -> bool
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        if let Some((owned_key, (val, expiry))) = self.map.remove_entry(key) {
+            self.total_weight -= self.weight_of(&owned_key, &val);
+            self.self_expiring_keys.remove(key);
+
+            // Remove just this key from its expiry bucket -- other keys may
+            // share the same (truncated) expiry, so only drop the bucket
+            // itself once it's empty.
+            let mut bucket_empty = false;
+            if let Some(key_list) = self.expiries.get_mut(&expiry) {
+                if let Some(index) = key_list.iter().position(|k| k.borrow() == key) {
+                    key_list.remove(index);
+                }
+                bucket_empty = key_list.is_empty();
+            }
+            if bucket_empty {
+                self.expiries.remove(&expiry);
+            }
+
+            self.notify_listener(owned_key, val, RemovalCause::Explicit);
             true
         } else {
             false
@@ -105,7 +394,7 @@ where
     }
 
     #[inline]
-    pub fn has_expired_items(&self) -> bool {
+    fn has_expired_skiplist_items(&self) -> bool {
         if let Some((expiry, _)) = self.expiries.front() {
             expiry <= &Utc::now()
         } else {
@@ -113,16 +402,83 @@ where
         }
     }
 
+    // Returns true if any self-expiring entry's value reports itself as expired
+    fn has_expired_self_expiring_items(&self) -> bool {
+        let is_expired_fn = match self.is_expired_fn {
+            Some(is_expired_fn) => is_expired_fn,
+            None => return false,
+        };
+        let now = Utc::now();
+        self.self_expiring_keys
+            .iter()
+            .any(|key| self.map.get(key).is_some_and(|(val, _)| is_expired_fn(val, now)))
+    }
+
+    #[inline]
+    pub fn has_expired_items(&self) -> bool {
+        self.has_expired_skiplist_items() || self.has_expired_self_expiring_items()
+    }
+
     pub fn remove_expired_items(&mut self) -> bool {
         let mut removed_items = false;
-        while self.has_expired_items() {
+        while self.has_expired_skiplist_items() {
             let (_, expired) = self.expiries.pop_front().unwrap();
 
             // Remove each expired key from the map
-            for key in expired.iter() {
-                if self.map.remove(&key).is_some() {
+            for key in expired.into_iter() {
+                if let Some((val, _)) = self.map.remove(&key) {
+                    self.total_weight -= self.weight_of(&key, &val);
+                    self.self_expiring_keys.remove(&key);
+                    self.counters.expirations.fetch_add(1, Ordering::Relaxed);
                     removed_items = true;
+                    self.notify_listener(key, val, RemovalCause::Expired);
+                }
+            }
+        }
+
+        if self.remove_expired_self_expiring_items() {
+            removed_items = true;
+        }
+
+        removed_items
+    }
+
+    // Removes any self-expiring entries whose value reports itself as expired
+    fn remove_expired_self_expiring_items(&mut self) -> bool {
+        let is_expired_fn = match self.is_expired_fn {
+            Some(is_expired_fn) => is_expired_fn,
+            None => return false,
+        };
+        let now = Utc::now();
+        let expired_keys: Vec<Key> = self
+            .self_expiring_keys
+            .iter()
+            .filter(|key| {
+                self.map
+                    .get(key)
+                    .is_some_and(|(val, _)| is_expired_fn(val, now))
+            })
+            .cloned()
+            .collect();
+
+        let mut removed_items = false;
+        for key in expired_keys {
+            self.self_expiring_keys.remove(&key);
+            if let Some((val, expiry)) = self.map.remove(&key) {
+                self.total_weight -= self.weight_of(&key, &val);
+                let mut bucket_empty = false;
+                if let Some(key_list) = self.expiries.get_mut(&expiry) {
+                    if let Some(index) = key_list.iter().position(|k| *k == key) {
+                        key_list.remove(index);
+                    }
+                    bucket_empty = key_list.is_empty();
                 }
+                if bucket_empty {
+                    self.expiries.remove(&expiry);
+                }
+                self.counters.expirations.fetch_add(1, Ordering::Relaxed);
+                removed_items = true;
+                self.notify_listener(key, val, RemovalCause::Expired);
             }
         }
         removed_items
@@ -148,18 +504,43 @@ where
         };
 
         if let Some(key) = key {
-            self.map.remove(&key);
+            if let Some((val, _)) = self.map.remove(&key) {
+                self.total_weight -= self.weight_of(&key, &val);
+                self.self_expiring_keys.remove(&key);
+                self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+                self.notify_listener(key, val, RemovalCause::Capacity);
+            }
         }
     }
 }
 
+impl<Key, Val> SyncCache<Key, Val>
+where
+    Key: Eq + Hash + Clone,
+    Val: CanExpire,
+{
+    // Insert a value that knows its own expiry (see `CanExpire`) instead of
+    // tracking a separate TTL for it. It's still placed in the `expiries`
+    // skiplist (far in the future, so count-based eviction only removes it
+    // after every TTL-based entry), but `has_expired_items`/`remove_expired_items`
+    // additionally consult `Val::is_expired` for keys inserted this way.
+    pub fn set_self_expiring(&mut self, key: Key, value: Val) -> bool {
+        if self.is_expired_fn.is_none() {
+            self.is_expired_fn = Some(Val::is_expired);
+        }
+        let had_key = self.set(key.clone(), value, self_expiring_sentinel_ttl());
+        self.self_expiring_keys.insert(key);
+        had_key
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn basic_get_set() {
-        let mut cache = SyncCache::with_capacity(5);
+        let mut cache = SyncCache::builder().capacity(5).build();
         cache.set("a", 1, Duration::hours(1));
         cache.set("b", 2, Duration::hours(1));
 
@@ -169,7 +550,7 @@ mod tests {
 
     #[test]
     fn has_expired_items() {
-        let mut cache = SyncCache::with_capacity(5);
+        let mut cache = SyncCache::builder().capacity(5).build();
         assert_eq!(cache.has_expired_items(), false);
 
         cache.set("a", 1, Duration::hours(1));
@@ -186,7 +567,7 @@ mod tests {
 
     #[test]
     fn remove_expired_items() {
-        let mut cache = SyncCache::with_capacity(5);
+        let mut cache = SyncCache::builder().capacity(5).build();
         cache.set("a", 1, Duration::hours(-1));
         cache.set("b", 2, Duration::hours(1));
         cache.set("c", 3, Duration::milliseconds(-1));
@@ -202,7 +583,7 @@ mod tests {
 
     #[test]
     fn eviction() {
-        let mut cache = SyncCache::with_capacity(3);
+        let mut cache = SyncCache::builder().capacity(3).build();
         cache.set("a", 1, Duration::hours(1));
         cache.set("b", 2, Duration::minutes(1));
         cache.set("c", 3, Duration::seconds(1));
@@ -213,9 +594,106 @@ mod tests {
         assert_eq!(cache.get(&"d"), Some(&4));
     }
 
+    struct SelfExpiring(bool);
+
+    impl CanExpire for SelfExpiring {
+        fn is_expired(&self, _now: DateTime<Utc>) -> bool {
+            self.0
+        }
+    }
+
+    #[test]
+    fn self_expiring_items() {
+        let mut cache = SyncCache::builder().capacity(5).build();
+        assert_eq!(cache.has_expired_items(), false);
+
+        cache.set_self_expiring("a", SelfExpiring(false));
+        assert_eq!(cache.has_expired_items(), false);
+
+        cache.set_self_expiring("b", SelfExpiring(true));
+        assert_eq!(cache.has_expired_items(), true);
+
+        assert_eq!(cache.remove_expired_items(), true);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&"a").is_some());
+        assert!(cache.get(&"b").is_none());
+    }
+
+    #[test]
+    fn weight_based_eviction() {
+        // entries weigh however many characters their value is
+        let mut cache = SyncCache::builder()
+            .weigher(10, |_key: &&str, val: &String| val.len())
+            .build();
+        cache.set("a", "xxxxx".to_string(), Duration::hours(1)); // weight 5
+        cache.set("b", "xxx".to_string(), Duration::seconds(1)); // weight 3, total 8
+        assert_eq!(cache.total_weight(), 8);
+        assert_eq!(cache.len(), 2);
+
+        // pushes total weight to 12, over the limit of 10, so "b" (expiring soonest) is evicted
+        cache.set("c", "xxxx".to_string(), Duration::minutes(1));
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.total_weight(), 9);
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn stats() {
+        let mut cache = SyncCache::builder().capacity(1).build();
+        cache.set("a", 1, Duration::milliseconds(-1));
+        cache.set("b", 2, Duration::hours(1)); // evicts "a" (over capacity)
+
+        assert_eq!(cache.get(&"b"), Some(&2));
+        assert_eq!(cache.get(&"a"), None);
+        cache.remove_expired_items();
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.evictions, 1);
+    }
+
+    #[test]
+    fn eviction_listener() {
+        let removed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let removed_clone = removed.clone();
+        let mut cache = SyncCache::builder()
+            .eviction_listener(move |key, val, cause| {
+                removed_clone.lock().unwrap().push((key, val, cause));
+            })
+            .build();
+
+        cache.set("a", 1, Duration::milliseconds(-1));
+        cache.set("b", 2, Duration::hours(1));
+        cache.set("b", 3, Duration::hours(1)); // replaces "b"
+        cache.remove(&"b");
+        cache.remove_expired_items(); // expires "a"
+
+        let removed = removed.lock().unwrap();
+        assert_eq!(
+            *removed,
+            vec![
+                ("b", 2, RemovalCause::Replaced),
+                ("b", 3, RemovalCause::Explicit),
+                ("a", 1, RemovalCause::Expired),
+            ]
+        );
+    }
+
+    #[test]
+    fn borrowed_key_lookup() {
+        let mut cache: SyncCache<String, i32> = SyncCache::builder().capacity(5).build();
+        cache.set("a".to_string(), 1, Duration::hours(1));
+
+        // `get`/`remove` accept `&str` directly, without allocating a `String`
+        assert_eq!(cache.get("a"), Some(&1));
+        assert_eq!(cache.remove("a"), true);
+        assert_eq!(cache.get("a"), None);
+    }
+
     #[test]
     fn multiple_keys_same_expiry() {
-        let mut cache = SyncCache::with_capacity(3);
+        let mut cache = SyncCache::builder().capacity(3).build();
         cache.set("a", 1, Duration::hours(-1));
         cache.set("b", 2, Duration::hours(-1));
         cache.set("c", 3, Duration::hours(1));
@@ -227,4 +705,23 @@ mod tests {
         assert_eq!(cache.get(&"a"), None);
         assert_eq!(cache.get(&"b"), None);
     }
+
+    #[test]
+    fn remove_one_of_multiple_keys_with_same_expiry() {
+        let mut cache = SyncCache::builder().capacity(3).build();
+        let expiry = Duration::hours(1);
+        cache.set("a", 1, expiry);
+        cache.set("b", 2, expiry);
+        assert_eq!(cache.expiries.len(), 1);
+
+        // Removing "a" must not drop "b" from the expiry index too, even
+        // though they share a bucket.
+        assert_eq!(cache.remove(&"a"), true);
+        assert_eq!(cache.get(&"b"), Some(&2));
+        assert_eq!(cache.expiries.len(), 1);
+
+        // Once "b" is also removed, its now-empty bucket goes with it.
+        assert_eq!(cache.remove(&"b"), true);
+        assert_eq!(cache.expiries.len(), 0);
+    }
 }